@@ -1,62 +1,291 @@
 use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
 
 use common::{BinarySerializable, DeserializeFrom};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
 use tantivy_bitpacker::{compute_num_bits, BitPacker, BitUnpacker};
 
 use crate::{FastFieldCodecReader, FastFieldCodecSerializer, FastFieldDataAccess, FastFieldStats};
 
 const BLOCK_SIZE: u64 = 128;
 
-#[derive(Clone)]
+/// Number of blocks grouped into a single chunk for the optional compression stage.
+const COMPRESSION_CHUNK_BLOCKS: u64 = 16;
+
+/// Bumped whenever `FORFooter`'s on-disk layout changes; `open_from_bytes` dispatches
+/// on this to pick the right `BlockMetadata` layout.
+const FOOTER_FORMAT_VERSION: u8 = 4;
+
+/// How groups of `COMPRESSION_CHUNK_BLOCKS` serialized blocks are further compressed on
+/// disk, on top of bit-packing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionMode {
+    None,
+    Deflate,
+    Lz4,
+}
+
+impl BinarySerializable for CompressionMode {
+    fn serialize<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        let tag: u8 = match self {
+            CompressionMode::None => 0,
+            CompressionMode::Deflate => 1,
+            CompressionMode::Lz4 => 2,
+        };
+        tag.serialize(write)
+    }
+
+    fn deserialize<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Ok(match u8::deserialize(reader)? {
+            1 => CompressionMode::Deflate,
+            2 => CompressionMode::Lz4,
+            _ => CompressionMode::None,
+        })
+    }
+}
+
+/// Compresses one chunk's raw bit-packed bytes with `mode`. `None` returns an
+/// unchanged copy of `raw`.
+fn compress_chunk(mode: CompressionMode, raw: &[u8]) -> io::Result<Vec<u8>> {
+    match mode {
+        CompressionMode::None => Ok(raw.to_vec()),
+        CompressionMode::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(raw)?;
+            encoder.finish()
+        }
+        CompressionMode::Lz4 => Ok(lz4_flex::compress_prepend_size(raw)),
+    }
+}
+
+/// Reverses `compress_chunk`.
+fn decompress_chunk(mode: CompressionMode, compressed: &[u8]) -> io::Result<Vec<u8>> {
+    match mode {
+        CompressionMode::None => Ok(compressed.to_vec()),
+        CompressionMode::Deflate => {
+            let mut decoder = DeflateDecoder::new(compressed);
+            let mut out = Vec::new();
+            decoder.read_to_end(&mut out)?;
+            Ok(out)
+        }
+        CompressionMode::Lz4 => lz4_flex::decompress_size_prepended(compressed)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+    }
+}
+
 pub struct FORFastFieldReader {
     num_vals: u64,
     min_value: u64,
     max_value: u64,
     block_readers: Vec<BlockReader>,
+    compression_mode: CompressionMode,
+    // Byte offset of each compressed chunk's start, one entry per group of
+    // `COMPRESSION_CHUNK_BLOCKS` blocks. Empty when `compression_mode` is `None`.
+    chunk_offsets: Vec<u64>,
+    // Decompressed bytes of the most recently accessed chunk, so repeated lookups into
+    // the same chunk (the common case for sequential or range scans) only pay the
+    // decompression cost once. Only ever populated when `compression_mode != None`. A
+    // `Mutex` (rather than `RefCell`) keeps the reader `Sync` so it can be shared behind
+    // an `Arc` across threads; the `Arc<[u8]>` payload makes a cache hit an O(1) refcount
+    // bump instead of a full chunk copy.
+    chunk_cache: Mutex<Option<(usize, Arc<[u8]>)>>,
+    // Byte offset, within the full column buffer, where the compressed data section
+    // ends and the footer begins. `data` passed to every reader method is the whole
+    // column including the trailing footer, so the last chunk's end can't default to
+    // `data.len()` without reading footer bytes as compressed payload.
+    compressed_data_end: u64,
+}
+
+impl Clone for FORFastFieldReader {
+    fn clone(&self) -> Self {
+        Self {
+            num_vals: self.num_vals,
+            min_value: self.min_value,
+            max_value: self.max_value,
+            block_readers: self.block_readers.clone(),
+            compression_mode: self.compression_mode,
+            chunk_offsets: self.chunk_offsets.clone(),
+            chunk_cache: Mutex::new(None),
+            compressed_data_end: self.compressed_data_end,
+        }
+    }
+}
+
+/// Either a borrow straight into the column's bytes (uncompressed chunks) or a shared
+/// handle to a decompressed chunk pulled from `FORFastFieldReader`'s cache.
+enum ChunkBytes<'a> {
+    Borrowed(&'a [u8]),
+    Cached(Arc<[u8]>),
+}
+
+impl<'a> Deref for ChunkBytes<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ChunkBytes::Borrowed(data) => data,
+            ChunkBytes::Cached(data) => data,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
 struct BlockMetadata {
+    min: u64,
+    max: u64,
+    num_bits: u8,
+    // First value of the block, verbatim. Only meaningful when `is_delta` is set.
+    base: u64,
+    // When set, the block bit-packs `values[i] - values[i - 1]` instead of `values[i] - min`.
+    is_delta: bool,
+}
+
+/// Layout of `BlockMetadata` before block maxima were persisted (format version 1,
+/// implicit: no version byte at all). Kept only to read old data in `open_from_bytes`.
+#[derive(Clone, Debug, Default)]
+struct LegacyBlockMetadata {
     min: u64,
     num_bits: u8,
 }
 
+impl BinarySerializable for LegacyBlockMetadata {
+    fn serialize<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        self.min.serialize(write)?;
+        self.num_bits.serialize(write)?;
+        Ok(())
+    }
+
+    fn deserialize<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let min = u64::deserialize(reader)?;
+        let num_bits = u8::deserialize(reader)?;
+        Ok(Self { min, num_bits })
+    }
+}
+
+/// Layout of `BlockMetadata` in format version 2: adds the per-block max used for
+/// range-skip pruning, but predates delta-mode blocks. Kept only to read that data in
+/// `open_from_bytes`.
 #[derive(Clone, Debug, Default)]
+struct V2BlockMetadata {
+    min: u64,
+    max: u64,
+    num_bits: u8,
+}
+
+impl BinarySerializable for V2BlockMetadata {
+    fn serialize<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        self.min.serialize(write)?;
+        self.max.serialize(write)?;
+        self.num_bits.serialize(write)?;
+        Ok(())
+    }
+
+    fn deserialize<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let min = u64::deserialize(reader)?;
+        let max = u64::deserialize(reader)?;
+        let num_bits = u8::deserialize(reader)?;
+        Ok(Self { min, max, num_bits })
+    }
+}
+
+#[derive(Debug, Default)]
 struct BlockReader {
     metadata: BlockMetadata,
     start_offset: u64,
     bit_unpacker: BitUnpacker,
+    // Number of values packed in this block. Needed to bound the prefix-sum decode
+    // below; plain blocks don't need it since every position there is already O(1).
+    len: u64,
+    // Absolute values of a delta block, decoded once and cached so repeated point
+    // lookups into the same block (e.g. random doc-id lookups over a monotonic column)
+    // are O(1) after the first. Only ever populated when `metadata.is_delta`. A `Mutex`
+    // (rather than `RefCell`) keeps `BlockReader` `Sync`, matching `FORFastFieldReader`'s
+    // chunk cache.
+    delta_cache: Mutex<Option<Arc<[u64]>>>,
+}
+
+impl Clone for BlockReader {
+    fn clone(&self) -> Self {
+        Self {
+            metadata: self.metadata.clone(),
+            start_offset: self.start_offset,
+            bit_unpacker: self.bit_unpacker.clone(),
+            len: self.len,
+            delta_cache: Mutex::new(None),
+        }
+    }
 }
 
 impl BlockReader {
-    fn new(metadata: BlockMetadata, start_offset: u64) -> Self {
+    fn new(metadata: BlockMetadata, start_offset: u64, len: u64) -> Self {
         Self {
             bit_unpacker: BitUnpacker::new(metadata.num_bits),
             metadata,
             start_offset,
+            len,
+            delta_cache: Mutex::new(None),
         }
     }
 
     #[inline]
     fn get_u64(&self, block_pos: u64, data: &[u8]) -> u64 {
-        let diff = self
-            .bit_unpacker
-            .get(block_pos, &data[self.start_offset as usize..]);
-        self.metadata.min + diff
+        if self.metadata.is_delta {
+            let block_data = &data[self.start_offset as usize..];
+            self.decode_delta_block(block_data)[block_pos as usize]
+        } else {
+            let diff = self
+                .bit_unpacker
+                .get(block_pos, &data[self.start_offset as usize..]);
+            self.metadata.min + diff
+        }
+    }
+
+    /// Returns this block's absolute values, prefix-summing the deltas once and caching
+    /// the result so later calls for the same block are a plain index instead of
+    /// another O(len) walk.
+    fn decode_delta_block(&self, block_data: &[u8]) -> Arc<[u64]> {
+        if let Some(cached) = self.delta_cache.lock().unwrap().as_ref() {
+            return cached.clone();
+        }
+        let mut values = Vec::with_capacity(self.len as usize);
+        let mut value = self.metadata.base;
+        values.push(value);
+        for delta_pos in 0..self.len - 1 {
+            value += self.bit_unpacker.get(delta_pos, block_data);
+            values.push(value);
+        }
+        let values: Arc<[u64]> = values.into();
+        *self.delta_cache.lock().unwrap() = Some(values.clone());
+        values
     }
 }
 
 impl BinarySerializable for BlockMetadata {
     fn serialize<W: Write>(&self, write: &mut W) -> io::Result<()> {
         self.min.serialize(write)?;
+        self.max.serialize(write)?;
         self.num_bits.serialize(write)?;
+        self.base.serialize(write)?;
+        self.is_delta.serialize(write)?;
         Ok(())
     }
 
     fn deserialize<R: Read>(reader: &mut R) -> io::Result<Self> {
         let min = u64::deserialize(reader)?;
+        let max = u64::deserialize(reader)?;
         let num_bits = u8::deserialize(reader)?;
-        Ok(Self { min, num_bits })
+        let base = u64::deserialize(reader)?;
+        let is_delta = bool::deserialize(reader)?;
+        Ok(Self {
+            min,
+            max,
+            num_bits,
+            base,
+            is_delta,
+        })
     }
 }
 
@@ -66,9 +295,678 @@ pub struct FORFooter {
     pub min_value: u64,
     pub max_value: u64,
     block_metadatas: Vec<BlockMetadata>,
+    compression_mode: CompressionMode,
+    chunk_offsets: Vec<u64>,
 }
 
 impl BinarySerializable for FORFooter {
+    fn serialize<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        let mut out = vec![];
+        FOOTER_FORMAT_VERSION.serialize(&mut out)?;
+        self.num_vals.serialize(&mut out)?;
+        self.min_value.serialize(&mut out)?;
+        self.max_value.serialize(&mut out)?;
+        self.block_metadatas.serialize(&mut out)?;
+        self.compression_mode.serialize(&mut out)?;
+        self.chunk_offsets.serialize(&mut out)?;
+        write.write_all(&out)?;
+        (out.len() as u32).serialize(write)?;
+        Ok(())
+    }
+
+    fn deserialize<R: Read>(reader: &mut R) -> io::Result<Self> {
+        // Version-1 footers have no leading version byte at all, so the first byte we
+        // see there is actually the low byte of `num_vals`. Buffer the whole footer so
+        // we can branch on the version without losing bytes we already read.
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+
+        match Self::try_versioned(&buf) {
+            Ok(Some(footer)) => return Ok(footer),
+            Ok(None) | Err(_) => {}
+        }
+
+        // Either a genuine version-1 footer, or one whose `num_vals` low byte happens
+        // to equal 2/3/4 and so looked like a versioned tag above (`try_versioned`
+        // already rejected that guess because it failed to parse or didn't consume the
+        // whole buffer).
+        // Parse as version 1 and require the same thing: every byte accounted for.
+        // That's the one property a spurious version match can't fake, so it's a real
+        // discriminator rather than hoping `num_vals`'s low byte avoids 2/3/4.
+        let mut body = &buf[..];
+        let num_vals = u64::deserialize(&mut body)?;
+        let min_value = u64::deserialize(&mut body)?;
+        let max_value = u64::deserialize(&mut body)?;
+        let legacy_metadatas = Vec::<LegacyBlockMetadata>::deserialize(&mut body)?;
+        if !body.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "FOR footer matches neither a known format version nor the legacy layout",
+            ));
+        }
+        // Version-1 blocks never stored a max, so we can't prune them without
+        // unpacking; falling back to the column max just means `docs_in_range`
+        // skips fewer blocks on old data instead of returning wrong results.
+        let block_metadatas = legacy_metadatas
+            .into_iter()
+            .map(|legacy| BlockMetadata {
+                min: legacy.min,
+                max: max_value,
+                num_bits: legacy.num_bits,
+                base: legacy.min,
+                is_delta: false,
+            })
+            .collect();
+        Ok(Self {
+            num_vals,
+            min_value,
+            max_value,
+            block_metadatas,
+            compression_mode: CompressionMode::None,
+            chunk_offsets: Vec::new(),
+        })
+    }
+}
+
+impl FORFooter {
+    /// Tries to parse `buf` as a version 2/3/4 footer. Returns `Ok(None)` when the
+    /// leading byte isn't a known version tag or when the versioned body doesn't
+    /// consume all of `buf`, and the error when the body can't be deserialized.
+    /// `FORFooter::deserialize` then tries the legacy layout, which is only accepted
+    /// if it consumes every byte.
+    fn try_versioned(buf: &[u8]) -> io::Result<Option<Self>> {
+        let mut body = &buf[..];
+        let format_version = match u8::deserialize(&mut body) {
+            Ok(format_version) => format_version,
+            // Too short to even hold a version byte; can't be anything but legacy.
+            Err(_) => return Ok(None),
+        };
+        if !matches!(format_version, 2 | 3 | FOOTER_FORMAT_VERSION) {
+            return Ok(None);
+        }
+
+        let footer = match format_version {
+            FOOTER_FORMAT_VERSION => {
+                let num_vals = u64::deserialize(&mut body)?;
+                let min_value = u64::deserialize(&mut body)?;
+                let max_value = u64::deserialize(&mut body)?;
+                let block_metadatas = Vec::<BlockMetadata>::deserialize(&mut body)?;
+                let compression_mode = CompressionMode::deserialize(&mut body)?;
+                let chunk_offsets = Vec::<u64>::deserialize(&mut body)?;
+                Self {
+                    num_vals,
+                    min_value,
+                    max_value,
+                    block_metadatas,
+                    compression_mode,
+                    chunk_offsets,
+                }
+            }
+            3 => {
+                let num_vals = u64::deserialize(&mut body)?;
+                let min_value = u64::deserialize(&mut body)?;
+                let max_value = u64::deserialize(&mut body)?;
+                let block_metadatas = Vec::<BlockMetadata>::deserialize(&mut body)?;
+                Self {
+                    num_vals,
+                    min_value,
+                    max_value,
+                    block_metadatas,
+                    compression_mode: CompressionMode::None,
+                    chunk_offsets: Vec::new(),
+                }
+            }
+            2 => {
+                let num_vals = u64::deserialize(&mut body)?;
+                let min_value = u64::deserialize(&mut body)?;
+                let max_value = u64::deserialize(&mut body)?;
+                let v2_metadatas = Vec::<V2BlockMetadata>::deserialize(&mut body)?;
+                let block_metadatas = v2_metadatas
+                    .into_iter()
+                    .map(|v2| BlockMetadata {
+                        min: v2.min,
+                        max: v2.max,
+                        num_bits: v2.num_bits,
+                        base: v2.min,
+                        is_delta: false,
+                    })
+                    .collect();
+                Self {
+                    num_vals,
+                    min_value,
+                    max_value,
+                    block_metadatas,
+                    compression_mode: CompressionMode::None,
+                    chunk_offsets: Vec::new(),
+                }
+            }
+            _ => unreachable!("format_version was already matched against the known tags above"),
+        };
+        Ok(if body.is_empty() { Some(footer) } else { None })
+    }
+}
+
+impl FastFieldCodecReader for FORFastFieldReader {
+    /// Opens a fast field given a file.
+    fn open_from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let footer_len: u32 = (&bytes[bytes.len() - 4..]).deserialize()?;
+        let compressed_data_end = bytes.len() - (4 + footer_len) as usize;
+        let mut footer = &bytes[compressed_data_end..bytes.len() - 4];
+        let footer = FORFooter::deserialize(&mut footer)?;
+        let num_vals = footer.num_vals;
+        let compression_mode = footer.compression_mode;
+        let mut block_readers = Vec::with_capacity(footer.block_metadatas.len());
+        let mut current_data_offset = 0u64;
+        for (block_idx, block_metadata) in footer.block_metadatas.into_iter().enumerate() {
+            // Each compressed chunk is decompressed independently, so a block's
+            // `start_offset` resets to 0 at the start of its chunk. Uncompressed columns
+            // are one implicit chunk spanning the whole data section, so the offset just
+            // keeps accumulating as before.
+            if compression_mode != CompressionMode::None
+                && block_idx > 0
+                && block_idx as u64 % COMPRESSION_CHUNK_BLOCKS == 0
+            {
+                current_data_offset = 0;
+            }
+            let block_start = block_idx as u64 * BLOCK_SIZE;
+            let block_len = BLOCK_SIZE.min(num_vals - block_start);
+            // Delta blocks store their first value verbatim in `base`, so pack one fewer.
+            let num_vals_packed = if block_metadata.is_delta {
+                block_len - 1
+            } else {
+                block_len
+            };
+            let num_bits = block_metadata.num_bits;
+            block_readers.push(BlockReader::new(block_metadata, current_data_offset, block_len));
+            current_data_offset += (num_vals_packed * num_bits as u64 + 7) / 8;
+        }
+        Ok(Self {
+            num_vals: footer.num_vals,
+            min_value: footer.min_value,
+            max_value: footer.max_value,
+            block_readers,
+            compression_mode,
+            chunk_offsets: footer.chunk_offsets,
+            chunk_cache: Mutex::new(None),
+            compressed_data_end: compressed_data_end as u64,
+        })
+    }
+
+    #[inline]
+    fn get_u64(&self, idx: u64, data: &[u8]) -> u64 {
+        let block_idx = (idx / BLOCK_SIZE) as usize;
+        let block_pos = idx - (block_idx as u64) * BLOCK_SIZE;
+        let chunk_idx = block_idx / COMPRESSION_CHUNK_BLOCKS as usize;
+        let chunk_data = self
+            .chunk_bytes(chunk_idx, data)
+            .expect("corrupt compressed FOR fast field chunk");
+        self.block_readers[block_idx].get_u64(block_pos, &chunk_data)
+    }
+
+    #[inline]
+    fn min_value(&self) -> u64 {
+        self.min_value
+    }
+    #[inline]
+    fn max_value(&self) -> u64 {
+        self.max_value
+    }
+}
+
+impl FORFastFieldReader {
+    /// Returns the decompressed bytes backing `chunk_idx`, using a single-entry cache so
+    /// repeated lookups into the same chunk (typical for sequential or range scans) don't
+    /// decompress more than once. When `compression_mode` is `None` there is nothing to
+    /// decompress, so this just borrows straight into `data` at zero extra cost.
+    fn chunk_bytes<'a>(&'a self, chunk_idx: usize, data: &'a [u8]) -> io::Result<ChunkBytes<'a>> {
+        if self.compression_mode == CompressionMode::None {
+            return Ok(ChunkBytes::Borrowed(data));
+        }
+        if let Some((cached_idx, cached_bytes)) = self.chunk_cache.lock().unwrap().as_ref() {
+            if *cached_idx == chunk_idx {
+                return Ok(ChunkBytes::Cached(cached_bytes.clone()));
+            }
+        }
+        let start = self.chunk_offsets[chunk_idx] as usize;
+        let end = self
+            .chunk_offsets
+            .get(chunk_idx + 1)
+            .copied()
+            .unwrap_or(self.compressed_data_end) as usize;
+        let decompressed: Arc<[u8]> = decompress_chunk(self.compression_mode, &data[start..end])?.into();
+        *self.chunk_cache.lock().unwrap() = Some((chunk_idx, decompressed.clone()));
+        Ok(ChunkBytes::Cached(decompressed))
+    }
+
+    /// Calls `callback` with every value in `[lo, hi]`, skipping blocks whose
+    /// `[min, max]` range doesn't intersect it.
+    pub fn docs_in_range(&self, lo: u64, hi: u64, data: &[u8], mut callback: impl FnMut(u64)) {
+        for (block_idx, block_reader) in self.block_readers.iter().enumerate() {
+            if block_reader.metadata.max < lo || block_reader.metadata.min > hi {
+                continue;
+            }
+            let chunk_idx = block_idx / COMPRESSION_CHUNK_BLOCKS as usize;
+            let chunk_data = self
+                .chunk_bytes(chunk_idx, data)
+                .expect("corrupt compressed FOR fast field chunk");
+            let block_start = block_idx as u64 * BLOCK_SIZE;
+            let block_len = BLOCK_SIZE.min(self.num_vals - block_start);
+            let block_data = &chunk_data[block_reader.start_offset as usize..];
+            if block_reader.metadata.is_delta {
+                // Walk the prefix sum once rather than re-decoding it per position.
+                let mut value = block_reader.metadata.base;
+                for block_pos in 0..block_len {
+                    if block_pos > 0 {
+                        value += block_reader.bit_unpacker.get(block_pos - 1, block_data);
+                    }
+                    if value >= lo && value <= hi {
+                        callback(value);
+                    }
+                }
+            } else {
+                for block_pos in 0..block_len {
+                    let value = block_reader.metadata.min
+                        + block_reader.bit_unpacker.get(block_pos, block_data);
+                    if value >= lo && value <= hi {
+                        callback(value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Decodes `len` consecutive values starting at `start` into `out`, a whole block at a
+    /// time rather than one value at a time.
+    pub fn get_range(&self, start: u64, len: usize, data: &[u8], out: &mut [u64]) {
+        assert_eq!(
+            out.len(),
+            len,
+            "`out` must have exactly `len` slots to decode into"
+        );
+        let end = start + len as u64;
+        let mut idx = start;
+        let mut out_pos = 0usize;
+        while idx < end {
+            let block_idx = (idx / BLOCK_SIZE) as usize;
+            let block_reader = &self.block_readers[block_idx];
+            let block_start = block_idx as u64 * BLOCK_SIZE;
+            let block_len = BLOCK_SIZE.min(self.num_vals - block_start);
+            let block_from = idx - block_start;
+            let block_to = block_len.min(end - block_start);
+            let num_vals_in_block = (block_to - block_from) as usize;
+
+            let dest = &mut out[out_pos..out_pos + num_vals_in_block];
+            let chunk_idx = block_idx / COMPRESSION_CHUNK_BLOCKS as usize;
+            let chunk_data = self
+                .chunk_bytes(chunk_idx, data)
+                .expect("corrupt compressed FOR fast field chunk");
+            let block_data = &chunk_data[block_reader.start_offset as usize..];
+
+            if block_reader.metadata.is_delta {
+                // Walk the prefix sum once: reach `block_from`, then fold forward.
+                let mut value = block_reader.metadata.base;
+                for delta_pos in 0..block_from {
+                    value += block_reader.bit_unpacker.get(delta_pos, block_data);
+                }
+                for (offset, slot) in dest.iter_mut().enumerate() {
+                    if offset > 0 {
+                        value += block_reader
+                            .bit_unpacker
+                            .get(block_from + offset as u64 - 1, block_data);
+                    }
+                    *slot = value;
+                }
+            } else {
+                for (offset, slot) in dest.iter_mut().enumerate() {
+                    *slot = block_reader
+                        .bit_unpacker
+                        .get(block_from + offset as u64, block_data);
+                }
+                for slot in dest.iter_mut() {
+                    *slot += block_reader.metadata.min;
+                }
+            }
+
+            out_pos += num_vals_in_block;
+            idx = block_start + block_to;
+        }
+    }
+}
+
+/// Picks plain FOR or delta FOR for one block, whichever needs fewer bits per value.
+/// Returns `(min, max, num_bits, base, is_delta, deltas)`.
+fn pick_block_encoding(block_values: &[u64]) -> (u64, u64, u8, u64, bool, Vec<u64>) {
+    let mut min = block_values[0];
+    let mut max = block_values[0];
+    for &value in &block_values[1..] {
+        min = min.min(value);
+        max = max.max(value);
+    }
+    let plain_num_bits = compute_num_bits(max - min);
+
+    let is_monotonic = block_values.windows(2).all(|pair| pair[1] >= pair[0]);
+    if block_values.len() > 1 && is_monotonic {
+        let max_delta = block_values
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .max()
+            .unwrap_or(0);
+        let delta_num_bits = compute_num_bits(max_delta);
+        if delta_num_bits < plain_num_bits {
+            let deltas = block_values.windows(2).map(|pair| pair[1] - pair[0]).collect();
+            return (min, max, delta_num_bits, block_values[0], true, deltas);
+        }
+    }
+
+    let deltas = block_values.iter().map(|&value| value - min).collect();
+    (min, max, plain_num_bits, block_values[0], false, deltas)
+}
+
+/// Same as LinearInterpolFastFieldSerializer, but working on chunks of CHUNK_SIZE elements.
+pub struct FORFastFieldSerializer {}
+
+impl FORFastFieldSerializer {
+    /// Same as `serialize`, but lets the caller opt into an extra byte-oriented
+    /// compression stage over groups of `COMPRESSION_CHUNK_BLOCKS` serialized blocks.
+    /// Passing `CompressionMode::None` writes the bit-packed blocks with no extra stage;
+    /// `serialize` picks the mode itself via `pick_compression_mode`.
+    pub fn serialize_with_compression(
+        write: &mut impl Write,
+        stats: FastFieldStats,
+        data_iter: impl Iterator<Item = u64>,
+        compression_mode: CompressionMode,
+    ) -> io::Result<()> {
+        let data = data_iter.collect::<Vec<_>>();
+        let blocks: Vec<&[u64]> = data.chunks(BLOCK_SIZE as usize).collect();
+        let mut block_metadatas = Vec::new();
+        let mut chunk_offsets = Vec::new();
+
+        if compression_mode == CompressionMode::None {
+            let mut bit_packer = BitPacker::new();
+            for &block_values in &blocks {
+                let (min, max, num_bits, base, is_delta, deltas) =
+                    pick_block_encoding(block_values);
+                for delta in &deltas {
+                    bit_packer.write(*delta, num_bits, write)?;
+                }
+                bit_packer.flush(write)?;
+                block_metadatas.push(BlockMetadata {
+                    min,
+                    max,
+                    num_bits,
+                    base,
+                    is_delta,
+                });
+            }
+            bit_packer.close(write)?;
+        } else {
+            let mut chunk_start = 0u64;
+            for block_group in blocks.chunks(COMPRESSION_CHUNK_BLOCKS as usize) {
+                let mut raw_chunk = Vec::new();
+                let mut bit_packer = BitPacker::new();
+                for &block_values in block_group {
+                    let (min, max, num_bits, base, is_delta, deltas) =
+                        pick_block_encoding(block_values);
+                    for delta in &deltas {
+                        bit_packer.write(*delta, num_bits, &mut raw_chunk)?;
+                    }
+                    bit_packer.flush(&mut raw_chunk)?;
+                    block_metadatas.push(BlockMetadata {
+                        min,
+                        max,
+                        num_bits,
+                        base,
+                        is_delta,
+                    });
+                }
+                bit_packer.close(&mut raw_chunk)?;
+
+                let compressed_chunk = compress_chunk(compression_mode, &raw_chunk)?;
+                chunk_offsets.push(chunk_start);
+                write.write_all(&compressed_chunk)?;
+                chunk_start += compressed_chunk.len() as u64;
+            }
+        }
+
+        let footer = FORFooter {
+            num_vals: stats.num_vals,
+            min_value: stats.min_value,
+            max_value: stats.max_value,
+            block_metadatas,
+            compression_mode,
+            chunk_offsets,
+        };
+        footer.serialize(write)?;
+        Ok(())
+    }
+
+    /// Same as `estimate_compression_ratio`, but samples a whole chunk of
+    /// `COMPRESSION_CHUNK_BLOCKS` blocks and compresses it with `compression_mode`
+    /// first, so callers can see whether the extra stage actually pays off for this
+    /// column before committing to it.
+    pub fn estimate_compression_ratio_with_compression(
+        fastfield_accessor: &impl FastFieldDataAccess,
+        stats: FastFieldStats,
+        compression_mode: CompressionMode,
+    ) -> f32 {
+        let num_vals_in_chunk = (COMPRESSION_CHUNK_BLOCKS * BLOCK_SIZE).min(stats.num_vals);
+        let sampled_values = (0..num_vals_in_chunk)
+            .into_iter()
+            .map(|pos| fastfield_accessor.get_val(pos as u64))
+            .collect::<Vec<_>>();
+
+        let mut raw_chunk = Vec::new();
+        let mut bit_packer = BitPacker::new();
+        let mut num_blocks_in_chunk = 0u64;
+        for block_values in sampled_values.chunks(BLOCK_SIZE as usize) {
+            let (_, _, num_bits, _, _, deltas) = pick_block_encoding(block_values);
+            for delta in &deltas {
+                // Writing to an in-memory `Vec` cannot fail.
+                bit_packer.write(*delta, num_bits, &mut raw_chunk).unwrap();
+            }
+            bit_packer.flush(&mut raw_chunk).unwrap();
+            num_blocks_in_chunk += 1;
+        }
+        bit_packer.close(&mut raw_chunk).unwrap();
+
+        let compressed_len = compress_chunk(compression_mode, &raw_chunk)
+            .expect("compressing an in-memory buffer cannot fail")
+            .len();
+        // block metadata: min (u64) + max (u64) + num_bits (u8) + base (u64) + is_delta (bool)
+        let metadata_bits = (8 + 8 + 1 + 8 + 1) * 8 * num_blocks_in_chunk;
+        let num_bits = compressed_len as u64 * 8 + metadata_bits;
+        let num_bits_uncompressed = 64 * sampled_values.len() as u64;
+        num_bits as f32 / num_bits_uncompressed as f32
+    }
+}
+
+impl FORFastFieldSerializer {
+    /// Samples the first chunk under `None`/`Deflate`/`Lz4` and returns whichever mode
+    /// compresses it smallest, so both `serialize` and `estimate_compression_ratio` make
+    /// the same call about whether the extra stage pays off for this column.
+    fn pick_compression_mode(
+        fastfield_accessor: &impl FastFieldDataAccess,
+        stats: FastFieldStats,
+    ) -> (CompressionMode, f32) {
+        [
+            CompressionMode::None,
+            CompressionMode::Deflate,
+            CompressionMode::Lz4,
+        ]
+        .into_iter()
+        .map(|mode| {
+            (
+                mode,
+                Self::estimate_compression_ratio_with_compression(fastfield_accessor, stats, mode),
+            )
+        })
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .expect("candidate list is non-empty")
+    }
+}
+
+impl FastFieldCodecSerializer for FORFastFieldSerializer {
+    const NAME: &'static str = "FOR";
+    const ID: u8 = 5;
+    /// Creates a new fast field serializer. Samples whether the optional compression
+    /// stage pays off for this column and writes whichever mode wins.
+    fn serialize(
+        write: &mut impl Write,
+        fastfield_accessor: &impl FastFieldDataAccess,
+        stats: FastFieldStats,
+        data_iter: impl Iterator<Item = u64>,
+        _data_iter1: impl Iterator<Item = u64>,
+    ) -> io::Result<()> {
+        let (compression_mode, _) = Self::pick_compression_mode(fastfield_accessor, stats);
+        Self::serialize_with_compression(write, stats, data_iter, compression_mode)
+    }
+
+    fn is_applicable(
+        _fastfield_accessor: &impl FastFieldDataAccess,
+        stats: FastFieldStats,
+    ) -> bool {
+        stats.num_vals > BLOCK_SIZE
+    }
+
+    /// Estimate compression ratio by compute the ratio of the first block, then check
+    /// whether the optional compression stage would shrink it further so codec
+    /// selection isn't blind to that layer.
+    fn estimate_compression_ratio(
+        fastfield_accessor: &impl FastFieldDataAccess,
+        stats: FastFieldStats,
+    ) -> f32 {
+        let last_elem_in_first_chunk = BLOCK_SIZE.min(stats.num_vals);
+        let sampled_values = (0..last_elem_in_first_chunk)
+            .into_iter()
+            .map(|pos| fastfield_accessor.get_val(pos as u64))
+            .collect::<Vec<_>>();
+
+        let max_distance = sampled_values
+            .iter()
+            .map(|&value| value - stats.min_value)
+            .max()
+            .unwrap();
+        // Estimate one block and multiply by a magic number 3 to select this codec
+        // when we are almost sure that this is relevant.
+        let relative_max_value = max_distance as f32 * 3.0;
+        let plain_num_bits = compute_num_bits(relative_max_value as u64);
+
+        // Monotonic (non-decreasing) data (sorted timestamps, doc-id mappings,
+        // ...) compresses far better as delta blocks than as plain FOR; detect that here
+        // too so this codec is still preferred for such columns.
+        let (_, _, delta_num_bits, _, is_delta, _) = pick_block_encoding(&sampled_values);
+        let num_bits_per_value = if is_delta {
+            delta_num_bits.min(plain_num_bits)
+        } else {
+            plain_num_bits
+        };
+
+        let num_bits = num_bits_per_value as u64 * stats.num_vals as u64
+            // block metadata: min (u64) + max (u64) + num_bits (u8) + base (u64) + is_delta (bool)
+            + (8 + 8 + 1 + 8 + 1) * 8 * (stats.num_vals / BLOCK_SIZE);
+        let num_bits_uncompressed = 64 * stats.num_vals;
+        let bit_packing_ratio = num_bits as f32 / num_bits_uncompressed as f32;
+
+        let (_, compressed_ratio) = Self::pick_compression_mode(fastfield_accessor, stats);
+        bit_packing_ratio.min(compressed_ratio)
+    }
+}
+
+/// Per-exception overhead: position (u8) + high bits (u64).
+const EXCEPTION_OVERHEAD_BITS: u64 = (1 + 8) * 8;
+
+/// Patched Frame-of-Reference: like FOR, but a block's bit width fits the bulk of its
+/// deltas, with outliers recorded as exceptions instead of widening the whole block.
+#[derive(Clone)]
+pub struct PFORFastFieldReader {
+    num_vals: u64,
+    min_value: u64,
+    max_value: u64,
+    block_readers: Vec<PForBlockReader>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct PForBlockMetadata {
+    min: u64,
+    num_bits: u8,
+    exceptions_offset: u64,
+    num_exceptions: u8,
+}
+
+#[derive(Clone, Debug, Default)]
+struct PForBlockReader {
+    metadata: PForBlockMetadata,
+    start_offset: u64,
+    bit_unpacker: BitUnpacker,
+    // (position within the block, bits above `num_bits`)
+    exceptions: Vec<(u8, u64)>,
+}
+
+impl PForBlockReader {
+    fn open(metadata: PForBlockMetadata, start_offset: u64, data: &[u8]) -> io::Result<Self> {
+        let mut exceptions_data = &data[metadata.exceptions_offset as usize..];
+        let mut exceptions = Vec::with_capacity(metadata.num_exceptions as usize);
+        for _ in 0..metadata.num_exceptions {
+            let position = u8::deserialize(&mut exceptions_data)?;
+            let high_bits = u64::deserialize(&mut exceptions_data)?;
+            exceptions.push((position, high_bits));
+        }
+        Ok(Self {
+            bit_unpacker: BitUnpacker::new(metadata.num_bits),
+            metadata,
+            start_offset,
+            exceptions,
+        })
+    }
+
+    #[inline]
+    fn get_u64(&self, block_pos: u64, data: &[u8]) -> u64 {
+        let mut diff = self
+            .bit_unpacker
+            .get(block_pos, &data[self.start_offset as usize..]);
+        if let Some(&(_, high_bits)) = self
+            .exceptions
+            .iter()
+            .find(|(position, _)| *position as u64 == block_pos)
+        {
+            diff |= high_bits << self.metadata.num_bits;
+        }
+        self.metadata.min + diff
+    }
+}
+
+impl BinarySerializable for PForBlockMetadata {
+    fn serialize<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        self.min.serialize(write)?;
+        self.num_bits.serialize(write)?;
+        self.exceptions_offset.serialize(write)?;
+        self.num_exceptions.serialize(write)?;
+        Ok(())
+    }
+
+    fn deserialize<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let min = u64::deserialize(reader)?;
+        let num_bits = u8::deserialize(reader)?;
+        let exceptions_offset = u64::deserialize(reader)?;
+        let num_exceptions = u8::deserialize(reader)?;
+        Ok(Self {
+            min,
+            num_bits,
+            exceptions_offset,
+            num_exceptions,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PFORFooter {
+    pub num_vals: u64,
+    pub min_value: u64,
+    pub max_value: u64,
+    block_metadatas: Vec<PForBlockMetadata>,
+}
+
+impl BinarySerializable for PFORFooter {
     fn serialize<W: Write>(&self, write: &mut W) -> io::Result<()> {
         let mut out = vec![];
         self.num_vals.serialize(&mut out)?;
@@ -85,24 +983,26 @@ impl BinarySerializable for FORFooter {
             num_vals: u64::deserialize(reader)?,
             min_value: u64::deserialize(reader)?,
             max_value: u64::deserialize(reader)?,
-            block_metadatas: Vec::<BlockMetadata>::deserialize(reader)?,
+            block_metadatas: Vec::<PForBlockMetadata>::deserialize(reader)?,
         };
         Ok(footer)
     }
 }
 
-impl FastFieldCodecReader for FORFastFieldReader {
+impl FastFieldCodecReader for PFORFastFieldReader {
     /// Opens a fast field given a file.
     fn open_from_bytes(bytes: &[u8]) -> io::Result<Self> {
         let footer_len: u32 = (&bytes[bytes.len() - 4..]).deserialize()?;
         let (_, mut footer) = bytes.split_at(bytes.len() - (4 + footer_len) as usize);
-        let footer = FORFooter::deserialize(&mut footer)?;
+        let footer = PFORFooter::deserialize(&mut footer)?;
         let mut block_readers = Vec::with_capacity(footer.block_metadatas.len());
         let mut current_data_offset = 0;
         for block_metadata in footer.block_metadatas {
-            let num_bits = block_metadata.num_bits;
-            block_readers.push(BlockReader::new(block_metadata, current_data_offset));
-            current_data_offset += num_bits as u64 * BLOCK_SIZE / 8;
+            let exceptions_offset = block_metadata.exceptions_offset;
+            let num_exceptions = block_metadata.num_exceptions;
+            let start_offset = current_data_offset;
+            block_readers.push(PForBlockReader::open(block_metadata, start_offset, bytes)?);
+            current_data_offset = exceptions_offset + num_exceptions as u64 * 9;
         }
         Ok(Self {
             num_vals: footer.num_vals,
@@ -130,12 +1030,49 @@ impl FastFieldCodecReader for FORFastFieldReader {
     }
 }
 
-/// Same as LinearInterpolFastFieldSerializer, but working on chunks of CHUNK_SIZE elements.
-pub struct FORFastFieldSerializer {}
+/// Picks the bit width minimizing packed size plus exception overhead; returns it
+/// along with the `(position_in_block, high_bits)` of every exception under that width.
+fn compute_optimal_encoding(deltas: &[u64]) -> (u8, Vec<(u8, u64)>) {
+    let max_delta = deltas.iter().copied().max().unwrap_or(0u64);
+    let max_bits = compute_num_bits(max_delta);
+    let mut best_bits = max_bits;
+    let mut best_cost = u64::MAX;
+    for candidate_bits in 0..=max_bits {
+        let threshold = if candidate_bits == 64 {
+            u64::MAX
+        } else {
+            (1u64 << candidate_bits) - 1
+        };
+        let num_exceptions = deltas.iter().filter(|&&delta| delta > threshold).count() as u64;
+        let cost =
+            deltas.len() as u64 * candidate_bits as u64 + num_exceptions * EXCEPTION_OVERHEAD_BITS;
+        if cost < best_cost {
+            best_cost = cost;
+            best_bits = candidate_bits;
+        }
+    }
+    let threshold = if best_bits == 64 {
+        u64::MAX
+    } else {
+        (1u64 << best_bits) - 1
+    };
+    let exceptions = deltas
+        .iter()
+        .enumerate()
+        .filter(|&(_, &delta)| delta > threshold)
+        .map(|(position, &delta)| (position as u8, delta >> best_bits))
+        .collect();
+    (best_bits, exceptions)
+}
 
-impl FastFieldCodecSerializer for FORFastFieldSerializer {
-    const NAME: &'static str = "FOR";
-    const ID: u8 = 5;
+/// Patched Frame-of-Reference serializer. Like `FORFastFieldSerializer`, but blocks with
+/// a handful of outlier deltas are bit-packed at the width that fits the rest of the
+/// block, with the outliers carried as exceptions instead of widening every value.
+pub struct PFORFastFieldSerializer {}
+
+impl FastFieldCodecSerializer for PFORFastFieldSerializer {
+    const NAME: &'static str = "PFOR";
+    const ID: u8 = 6;
     /// Creates a new fast field serializer.
     fn serialize(
         write: &mut impl Write,
@@ -147,25 +1084,48 @@ impl FastFieldCodecSerializer for FORFastFieldSerializer {
         let data = data_iter.collect::<Vec<_>>();
         let mut bit_packer = BitPacker::new();
         let mut block_metadatas = Vec::new();
+        let mut data_offset = 0u64;
         for data_pos in (0..data.len() as u64).step_by(BLOCK_SIZE as usize) {
             let block_num_vals = BLOCK_SIZE.min(data.len() as u64 - data_pos) as usize;
             let block_values = &data[data_pos as usize..data_pos as usize + block_num_vals];
             let mut min = block_values[0];
-            let mut max = block_values[0];
             for &current_value in block_values[1..].iter() {
                 min = min.min(current_value);
-                max = max.max(current_value);
             }
-            let num_bits = compute_num_bits(max - min);
-            for current_value in block_values.iter() {
-                bit_packer.write(current_value - min, num_bits, write)?;
+            let deltas = block_values
+                .iter()
+                .map(|&value| value - min)
+                .collect::<Vec<_>>();
+            let (num_bits, exceptions) = compute_optimal_encoding(&deltas);
+
+            for &delta in &deltas {
+                let truncated = if num_bits == 64 {
+                    delta
+                } else {
+                    delta & ((1u64 << num_bits) - 1)
+                };
+                bit_packer.write(truncated, num_bits, write)?;
             }
             bit_packer.flush(write)?;
-            block_metadatas.push(BlockMetadata { min, num_bits });
+            data_offset += (block_num_vals as u64 * num_bits as u64 + 7) / 8;
+
+            let exceptions_offset = data_offset;
+            for &(position, high_bits) in &exceptions {
+                position.serialize(write)?;
+                high_bits.serialize(write)?;
+            }
+            data_offset = exceptions_offset + exceptions.len() as u64 * 9;
+
+            block_metadatas.push(PForBlockMetadata {
+                min,
+                num_bits,
+                exceptions_offset,
+                num_exceptions: exceptions.len() as u8,
+            });
         }
         bit_packer.close(write)?;
 
-        let footer = FORFooter {
+        let footer = PFORFooter {
             num_vals: stats.num_vals,
             min_value: stats.min_value,
             max_value: stats.max_value,
@@ -182,28 +1142,25 @@ impl FastFieldCodecSerializer for FORFastFieldSerializer {
         stats.num_vals > BLOCK_SIZE
     }
 
-    /// Estimate compression ratio by compute the ratio of the first block.
+    /// Estimate compression ratio from the first block, modeling the exception
+    /// overhead so this codec is preferred over plain FOR on data with sparse outliers.
     fn estimate_compression_ratio(
         fastfield_accessor: &impl FastFieldDataAccess,
         stats: FastFieldStats,
     ) -> f32 {
         let last_elem_in_first_chunk = BLOCK_SIZE.min(stats.num_vals);
-        let max_distance = (0..last_elem_in_first_chunk)
+        let deltas = (0..last_elem_in_first_chunk)
             .into_iter()
-            .map(|pos| {
-                let actual_value = fastfield_accessor.get_val(pos as u64);
-                actual_value - stats.min_value
-            })
-            .max()
-            .unwrap();
-
-        // Estimate one block and multiply by a magic number 3 to select this codec
-        // when we are almost sure that this is relevant.
-        let relative_max_value = max_distance as f32 * 3.0;
+            .map(|pos| fastfield_accessor.get_val(pos as u64) - stats.min_value)
+            .collect::<Vec<_>>();
+        let (num_bits, exceptions) = compute_optimal_encoding(&deltas);
 
-        let num_bits = compute_num_bits(relative_max_value as u64) as u64 * stats.num_vals as u64
-            // function metadata per block
-            + 9 * (stats.num_vals / BLOCK_SIZE);
+        let num_blocks = (stats.num_vals / BLOCK_SIZE).max(1);
+        let bits_per_block = num_bits as u64 * BLOCK_SIZE
+            // block metadata: min (u64) + num_bits (u8) + exceptions_offset (u64) + num_exceptions (u8)
+            + (8 + 1 + 8 + 1) * 8
+            + exceptions.len() as u64 * EXCEPTION_OVERHEAD_BITS;
+        let num_bits = bits_per_block * num_blocks;
         let num_bits_uncompressed = 64 * stats.num_vals;
         num_bits as f32 / num_bits_uncompressed as f32
     }
@@ -218,6 +1175,12 @@ mod tests {
         crate::tests::create_and_validate::<FORFastFieldSerializer, FORFastFieldReader>(data, name)
     }
 
+    fn create_and_validate_pfor(data: &[u64], name: &str) -> (f32, f32) {
+        crate::tests::create_and_validate::<PFORFastFieldSerializer, PFORFastFieldReader>(
+            data, name,
+        )
+    }
+
     #[test]
     fn test_compression() {
         let data = (10..=6_000_u64).collect::<Vec<_>>();
@@ -269,4 +1232,461 @@ mod tests {
             create_and_validate(&data, "random");
         }
     }
+
+    #[test]
+    fn test_docs_in_range() {
+        let mut data = vec![];
+        let mut bit_packer = BitPacker::new();
+        let block0_min = 0u64;
+        let block0_max = 127u64;
+        let num_bits0 = compute_num_bits(block0_max - block0_min);
+        for value in 0..128u64 {
+            bit_packer
+                .write(value - block0_min, num_bits0, &mut data)
+                .unwrap();
+        }
+        bit_packer.flush(&mut data).unwrap();
+        let block1_min = 1000u64;
+        let block1_max = 1127u64;
+        let num_bits1 = compute_num_bits(block1_max - block1_min);
+        for value in 1000..1128u64 {
+            bit_packer
+                .write(value - block1_min, num_bits1, &mut data)
+                .unwrap();
+        }
+        bit_packer.flush(&mut data).unwrap();
+        bit_packer.close(&mut data).unwrap();
+
+        let block_readers = vec![
+            BlockReader::new(
+                BlockMetadata {
+                    min: block0_min,
+                    max: block0_max,
+                    num_bits: num_bits0,
+                    base: block0_min,
+                    is_delta: false,
+                },
+                0,
+                128,
+            ),
+            BlockReader::new(
+                BlockMetadata {
+                    min: block1_min,
+                    max: block1_max,
+                    num_bits: num_bits1,
+                    base: block1_min,
+                    is_delta: false,
+                },
+                num_bits0 as u64 * BLOCK_SIZE / 8,
+                128,
+            ),
+        ];
+        let reader = FORFastFieldReader {
+            num_vals: 256,
+            min_value: block0_min,
+            max_value: block1_max,
+            block_readers,
+            compression_mode: CompressionMode::None,
+            chunk_offsets: Vec::new(),
+            chunk_cache: Mutex::new(None),
+            compressed_data_end: data.len() as u64,
+        };
+
+        let mut matched = vec![];
+        reader.docs_in_range(1_050, 1_060, &data, |value| matched.push(value));
+        matched.sort_unstable();
+        assert_eq!(matched, (1_050..=1_060).collect::<Vec<_>>());
+
+        let mut none_matched = vec![];
+        reader.docs_in_range(5_000, 6_000, &data, |value| none_matched.push(value));
+        assert!(none_matched.is_empty());
+    }
+
+    #[test]
+    fn test_get_range() {
+        let data = (0..10_000_u64).collect::<Vec<_>>();
+        let mut out_bytes = vec![];
+        let mut bit_packer = BitPacker::new();
+        let mut block_metadatas = Vec::new();
+        for block_values in data.chunks(BLOCK_SIZE as usize) {
+            let min = *block_values.iter().min().unwrap();
+            let max = *block_values.iter().max().unwrap();
+            let num_bits = compute_num_bits(max - min);
+            for &value in block_values {
+                bit_packer
+                    .write(value - min, num_bits, &mut out_bytes)
+                    .unwrap();
+            }
+            bit_packer.flush(&mut out_bytes).unwrap();
+            block_metadatas.push(BlockMetadata {
+                min,
+                max,
+                num_bits,
+                base: min,
+                is_delta: false,
+            });
+        }
+        bit_packer.close(&mut out_bytes).unwrap();
+
+        let mut block_readers = Vec::with_capacity(block_metadatas.len());
+        let mut current_data_offset = 0;
+        for (block_idx, block_metadata) in block_metadatas.into_iter().enumerate() {
+            let num_bits = block_metadata.num_bits;
+            let block_start = block_idx as u64 * BLOCK_SIZE;
+            let block_len = BLOCK_SIZE.min(data.len() as u64 - block_start);
+            block_readers.push(BlockReader::new(block_metadata, current_data_offset, block_len));
+            current_data_offset += num_bits as u64 * BLOCK_SIZE / 8;
+        }
+        let reader = FORFastFieldReader {
+            num_vals: data.len() as u64,
+            min_value: *data.iter().min().unwrap(),
+            max_value: *data.iter().max().unwrap(),
+            block_readers,
+            compression_mode: CompressionMode::None,
+            chunk_offsets: Vec::new(),
+            chunk_cache: Mutex::new(None),
+            compressed_data_end: out_bytes.len() as u64,
+        };
+
+        // Range spanning a partial head block, several full blocks and a partial tail
+        // block, exercising every branch of `get_range`.
+        let start = 100u64;
+        let len = 500usize;
+        let mut out = vec![0u64; len];
+        reader.get_range(start, len, &out_bytes, &mut out);
+        assert_eq!(out, data[start as usize..start as usize + len]);
+
+        let mut single_block = vec![0u64; BLOCK_SIZE as usize];
+        reader.get_range(0, BLOCK_SIZE as usize, &out_bytes, &mut single_block);
+        assert_eq!(single_block, data[0..BLOCK_SIZE as usize]);
+    }
+
+    fn serialize_for(data: &[u64]) -> (Vec<u8>, FORFastFieldReader) {
+        let stats = FastFieldStats {
+            num_vals: data.len() as u64,
+            min_value: *data.iter().min().unwrap(),
+            max_value: *data.iter().max().unwrap(),
+        };
+        let mut bytes = vec![];
+        FORFastFieldSerializer::serialize_with_compression(
+            &mut bytes,
+            stats,
+            data.iter().copied(),
+            CompressionMode::None,
+        )
+        .unwrap();
+        let reader = FORFastFieldReader::open_from_bytes(&bytes).unwrap();
+        (bytes, reader)
+    }
+
+    #[test]
+    fn test_get_range_delta_blocks() {
+        // Monotonically increasing with a small stride, so every block picks delta
+        // encoding; exercises `get_range`'s prefix-sum decode path.
+        let data = (0..10_000_u64).map(|i| i * 3).collect::<Vec<_>>();
+        let (bytes, reader) = serialize_for(&data);
+        assert!(reader.block_readers.iter().all(|b| b.metadata.is_delta));
+
+        let start = 100u64;
+        let len = 500usize;
+        let mut out = vec![0u64; len];
+        reader.get_range(start, len, &bytes, &mut out);
+        assert_eq!(out, data[start as usize..start as usize + len]);
+    }
+
+    #[test]
+    fn test_docs_in_range_delta_blocks() {
+        // Same delta-encoded data as `test_get_range_delta_blocks`, exercising
+        // `docs_in_range`'s prefix-sum walk over delta blocks.
+        let data = (0..10_000_u64).map(|i| i * 3).collect::<Vec<_>>();
+        let (bytes, reader) = serialize_for(&data);
+        assert!(reader.block_readers.iter().all(|b| b.metadata.is_delta));
+
+        let lo = 1_000u64;
+        let hi = 1_100u64;
+        let mut matched = vec![];
+        reader.docs_in_range(lo, hi, &bytes, |value| matched.push(value));
+        matched.sort_unstable();
+        let expected = data
+            .iter()
+            .copied()
+            .filter(|&v| v >= lo && v <= hi)
+            .collect::<Vec<_>>();
+        assert_eq!(matched, expected);
+    }
+
+    #[test]
+    fn test_pfor_compression_with_outliers() {
+        // Mostly tightly clustered values with a handful of large spikes: plain FOR has
+        // to widen every value in the block to fit the spikes, PFOR should not.
+        let mut data = vec![10_u64; 10 * BLOCK_SIZE as usize];
+        for i in (0..data.len()).step_by(37) {
+            data[i] = 1_000_000 + i as u64;
+        }
+        let (pfor_estimate, pfor_compression) = create_and_validate_pfor(&data, "sparse outliers");
+        let (for_estimate, for_compression) = create_and_validate(&data, "sparse outliers");
+        assert!(pfor_compression < for_compression);
+        assert!(pfor_estimate < for_estimate);
+    }
+
+    #[test]
+    fn test_pfor_with_codec_data_sets() {
+        let data_sets = get_codec_test_data_sets();
+        for (mut data, name) in data_sets {
+            create_and_validate_pfor(&data, name);
+            data.reverse();
+            create_and_validate_pfor(&data, name);
+        }
+    }
+
+    #[test]
+    fn test_pfor_simple() {
+        let data = (10..=20_u64).collect::<Vec<_>>();
+        create_and_validate_pfor(&data, "simple monotonically");
+    }
+
+    #[test]
+    fn pfor_border_cases() {
+        let data = (0..1024).collect::<Vec<_>>();
+        create_and_validate_pfor(&data, "border case");
+        let data = (0..1025).collect::<Vec<_>>();
+        create_and_validate_pfor(&data, "border case");
+    }
+
+    #[test]
+    fn test_pfor_full_width_block() {
+        // `min..max` spans (almost) the full u64 domain, so `compute_num_bits` returns
+        // 64 for this block and `compute_optimal_encoding` must not shift by 64 while
+        // searching candidate widths.
+        let mut data = vec![0_u64; BLOCK_SIZE as usize];
+        data[0] = u64::MAX;
+        create_and_validate_pfor(&data, "full width block");
+    }
+
+    #[test]
+    fn test_delta_mode_timestamps() {
+        // Monotonically increasing with a small, tight stride: each block's deltas are
+        // far narrower than the absolute values, so delta mode should cut the encoded
+        // size down noticeably versus plain FOR's `value - min`.
+        let data = (0..20_000_u64).map(|i| 1_700_000_000 + i * 3).collect::<Vec<_>>();
+        let (_, delta_compression) = create_and_validate(&data, "sorted timestamps");
+        // Plain FOR would need ~9 bits/value here (block range is ~127 * stride); delta
+        // mode needs ~2 bits/value (the stride itself), so even with per-block metadata
+        // overhead the encoded size should land well under a quarter of the raw size.
+        assert!(delta_compression < 0.1);
+    }
+
+    #[test]
+    fn test_delta_mode_mixed_with_plain_blocks() {
+        // Alternate small-stride monotonic blocks (favor delta) with blocks that start
+        // at 0 and then jump up, whose first delta is as wide as the block's whole
+        // range (favor plain FOR), so both `is_delta` states coexist within one column.
+        let mut data = Vec::new();
+        for block in 0..20u64 {
+            if block % 2 == 0 {
+                data.extend((0..BLOCK_SIZE).map(|i| block * 1_000_000 + i));
+            } else {
+                data.extend((0..BLOCK_SIZE).map(|i| if i == 0 { 0 } else { block * 1_000_000 + i }));
+            }
+        }
+        create_and_validate(&data, "mixed delta and plain blocks");
+    }
+
+    #[test]
+    fn test_compression_modes_roundtrip() {
+        // Repetitive, clustered values: not much left for bit-packing alone to squeeze
+        // out, so this also exercises that the extra compression stage doesn't corrupt
+        // anything even when it barely helps.
+        let data = (0..4_000_u64).map(|i| (i * 7) % 5_000).collect::<Vec<_>>();
+        for mode in [
+            CompressionMode::None,
+            CompressionMode::Deflate,
+            CompressionMode::Lz4,
+        ] {
+            let stats = FastFieldStats {
+                num_vals: data.len() as u64,
+                min_value: *data.iter().min().unwrap(),
+                max_value: *data.iter().max().unwrap(),
+            };
+            let mut bytes = vec![];
+            FORFastFieldSerializer::serialize_with_compression(
+                &mut bytes,
+                stats,
+                data.iter().copied(),
+                mode,
+            )
+            .unwrap();
+            let reader = FORFastFieldReader::open_from_bytes(&bytes).unwrap();
+            for (idx, &expected) in data.iter().enumerate() {
+                assert_eq!(
+                    reader.get_u64(idx as u64, &bytes),
+                    expected,
+                    "mode {:?}, idx {}",
+                    mode,
+                    idx
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_open_legacy_footer_layouts() {
+        // Version 2: no delta mode, but block maxima are present.
+        let mut bytes = vec![];
+        2u8.serialize(&mut bytes).unwrap();
+        3u64.serialize(&mut bytes).unwrap(); // num_vals
+        0u64.serialize(&mut bytes).unwrap(); // min_value
+        20u64.serialize(&mut bytes).unwrap(); // max_value
+        vec![V2BlockMetadata {
+            min: 0,
+            max: 20,
+            num_bits: 5,
+        }]
+        .serialize(&mut bytes)
+        .unwrap();
+        let footer_len = bytes.len() as u32;
+        (footer_len).serialize(&mut bytes).unwrap();
+        let footer = FORFooter::deserialize(&mut &bytes[..footer_len as usize]).unwrap();
+        assert_eq!(footer.num_vals, 3);
+        assert_eq!(footer.block_metadatas.len(), 1);
+        assert_eq!(footer.block_metadatas[0].max, 20);
+        assert!(!footer.block_metadatas[0].is_delta);
+        assert_eq!(footer.compression_mode, CompressionMode::None);
+
+        // Version 1 (implicit, no version byte matching FOOTER_FORMAT_VERSION/3/2):
+        // block maxima aren't stored at all, so they fall back to the column max.
+        let mut bytes = vec![];
+        5u64.serialize(&mut bytes).unwrap(); // num_vals
+        0u64.serialize(&mut bytes).unwrap(); // min_value
+        20u64.serialize(&mut bytes).unwrap(); // max_value
+        vec![LegacyBlockMetadata { min: 0, num_bits: 5 }]
+            .serialize(&mut bytes)
+            .unwrap();
+        let footer_len = bytes.len() as u32;
+        (footer_len).serialize(&mut bytes).unwrap();
+        let footer = FORFooter::deserialize(&mut &bytes[..footer_len as usize]).unwrap();
+        assert_eq!(footer.num_vals, 5);
+        assert_eq!(footer.block_metadatas.len(), 1);
+        assert_eq!(footer.block_metadatas[0].max, footer.max_value);
+        assert!(!footer.block_metadatas[0].is_delta);
+    }
+
+    #[test]
+    fn test_open_legacy_footer_num_vals_collides_with_version_tag() {
+        // A genuine legacy (version 1) footer whose `num_vals` low byte happens to equal
+        // 2, 3, or 4 used to be misparsed as that newer version instead of erroring or
+        // falling back to the legacy layout, silently corrupting every block's
+        // min/max/bit-width/base. `FORFooter::deserialize` must recognize these by full
+        // consumption of the footer body, not by hoping `num_vals` avoids those bytes.
+        for colliding_num_vals in [2u64, 3u64, 4u64] {
+            let mut bytes = vec![];
+            colliding_num_vals.serialize(&mut bytes).unwrap();
+            0u64.serialize(&mut bytes).unwrap(); // min_value
+            20u64.serialize(&mut bytes).unwrap(); // max_value
+            vec![LegacyBlockMetadata { min: 0, num_bits: 5 }]
+                .serialize(&mut bytes)
+                .unwrap();
+            let footer_len = bytes.len() as u32;
+            (footer_len).serialize(&mut bytes).unwrap();
+            let footer = FORFooter::deserialize(&mut &bytes[..footer_len as usize]).unwrap();
+            assert_eq!(footer.num_vals, colliding_num_vals);
+            assert_eq!(footer.block_metadatas.len(), 1);
+            assert_eq!(footer.block_metadatas[0].min, 0);
+            assert_eq!(footer.block_metadatas[0].num_bits, 5);
+            assert_eq!(footer.block_metadatas[0].max, footer.max_value);
+            assert!(!footer.block_metadatas[0].is_delta);
+            assert_eq!(footer.compression_mode, CompressionMode::None);
+        }
+    }
+
+    #[test]
+    fn test_open_truncated_versioned_footer_errors() {
+        // A genuine version-4 footer (unambiguous version byte) whose body got cut
+        // off mid-write, e.g. a `block_metadatas` vec that lost its tail. Unlike the
+        // `num_vals` collision above, there's no legacy layout this could plausibly
+        // be; `try_versioned` must report it as corrupt instead of silently
+        // reinterpreting the truncated bytes as a legacy footer.
+        let footer = FORFooter {
+            num_vals: 1_000,
+            min_value: 0,
+            max_value: 20,
+            block_metadatas: vec![
+                BlockMetadata {
+                    min: 0,
+                    max: 20,
+                    num_bits: 5,
+                    base: 0,
+                    is_delta: false,
+                };
+                8
+            ],
+            compression_mode: CompressionMode::None,
+            chunk_offsets: Vec::new(),
+        };
+        let mut bytes = vec![];
+        footer.serialize(&mut bytes).unwrap();
+        // Drop the trailing length marker plus enough of the body that the
+        // `block_metadatas` vec can no longer be read back in full.
+        bytes.truncate(bytes.len() - 4 - 20);
+
+        assert!(FORFooter::deserialize(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_range_apis_multi_chunk_compressed() {
+        // Enough blocks to span several compression chunks (COMPRESSION_CHUNK_BLOCKS
+        // blocks per chunk), with monotonic data so every block picks delta encoding.
+        // This exercises `docs_in_range`/`get_range` recomputing `chunk_idx` and
+        // re-decompressing across chunk boundaries together with the delta
+        // prefix-sum decode, not just `get_u64` in isolation like
+        // `test_compression_modes_roundtrip` does.
+        let data = (0..20 * BLOCK_SIZE).map(|i| i * 3).collect::<Vec<_>>();
+        for mode in [CompressionMode::Deflate, CompressionMode::Lz4] {
+            let stats = FastFieldStats {
+                num_vals: data.len() as u64,
+                min_value: *data.iter().min().unwrap(),
+                max_value: *data.iter().max().unwrap(),
+            };
+            let mut bytes = vec![];
+            FORFastFieldSerializer::serialize_with_compression(
+                &mut bytes,
+                stats,
+                data.iter().copied(),
+                mode,
+            )
+            .unwrap();
+            let reader = FORFastFieldReader::open_from_bytes(&bytes).unwrap();
+            assert!(reader.chunk_offsets.len() > 1, "mode {:?}", mode);
+            assert!(
+                reader.block_readers.iter().all(|b| b.metadata.is_delta),
+                "mode {:?}",
+                mode
+            );
+
+            // `get_range` over a span crossing a chunk boundary.
+            let start = 100u64;
+            let len = 18 * BLOCK_SIZE as usize - 50;
+            let mut out = vec![0u64; len];
+            reader.get_range(start, len, &bytes, &mut out);
+            assert_eq!(
+                out,
+                data[start as usize..start as usize + len],
+                "mode {:?}",
+                mode
+            );
+
+            // `docs_in_range` over values that only live in a later chunk.
+            let lo = 18 * BLOCK_SIZE * 3;
+            let hi = lo + 100;
+            let mut matched = vec![];
+            reader.docs_in_range(lo, hi, &bytes, |value| matched.push(value));
+            matched.sort_unstable();
+            let expected = data
+                .iter()
+                .copied()
+                .filter(|&v| v >= lo && v <= hi)
+                .collect::<Vec<_>>();
+            assert_eq!(matched, expected, "mode {:?}", mode);
+        }
+    }
 }